@@ -28,14 +28,20 @@ use std::error::Error;
 use ldap_types::basic::LDAPEntry;
 use ldap_types::basic::OIDWithLength;
 use ldap_types::filter::search_filter_parser;
+use ldap_types::schema::LDAPSchema;
 use ldap_utils::apply_ldap_operations;
 use ldap_utils::diff_entries;
-use ldap_utils::search_entries;
+use ldap_utils::LDAPOperation;
 use ldap_utils::{
     connect_with_parameters, parse_scope, query_ldap_schema, query_root_dse,
     toml_connect_parameters,
 };
 
+use ldap3::controls::RawControl;
+use ldap3::Mod;
+use ldap3::SearchEntry;
+use ldap3::SearchOptions;
+
 use lazy_static::lazy_static;
 
 use tracing::instrument;
@@ -50,6 +56,81 @@ use oid::ObjectIdentifier;
 
 use std::convert::TryFrom;
 
+use serde::Deserialize;
+
+lazy_static! {
+    /// the LDAP syntax OID for DN values (RFC 4517 section 3.3.9), used to
+    /// recognize DN-valued attributes when rewriting RDN components during
+    /// attribute renaming
+    static ref DN_SYNTAX_OID: OIDWithLength = OIDWithLength {
+        oid: ObjectIdentifier::try_from("1.3.6.1.4.1.1466.115.121.1.12").unwrap(),
+        length: None
+    };
+}
+
+/// Alias dereferencing behavior for LDAP searches, mirroring the standard
+/// `derefAliases` values of the LDAP search request (RFC 4511 section
+/// 4.5.1.3: `never` = 0, `search` = 1, `find` = 2, `always` = 3)
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DerefAliases {
+    /// never dereference aliases
+    Never,
+    /// dereference aliases when searching subordinates of the base object, but not in locating the base object itself
+    Search,
+    /// dereference aliases in locating the base object of the search, but not when searching subordinates of the base object
+    Find,
+    /// always dereference aliases, both in locating the base object and when searching subordinates
+    Always,
+}
+
+impl std::fmt::Display for DerefAliases {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DerefAliases::Never => write!(f, "never"),
+            DerefAliases::Search => write!(f, "search"),
+            DerefAliases::Find => write!(f, "find"),
+            DerefAliases::Always => write!(f, "always"),
+        }
+    }
+}
+
+/// Maps our CLI-facing `DerefAliases` onto the `ldap3` crate's own
+/// `derefAliases` type, so it can be applied to a search through
+/// `ldap3::SearchOptions` instead of being passed as a bin-crate-local type
+impl From<DerefAliases> for ldap3::DerefAliases {
+    fn from(deref: DerefAliases) -> Self {
+        match deref {
+            DerefAliases::Never => ldap3::DerefAliases::Never,
+            DerefAliases::Search => ldap3::DerefAliases::Search,
+            DerefAliases::Find => ldap3::DerefAliases::Find,
+            DerefAliases::Always => ldap3::DerefAliases::Always,
+        }
+    }
+}
+
+/// How to react to attribute type and object class incompatibilities found
+/// between the source and destination schemas during the pre-sync schema
+/// compatibility check
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SchemaCheckMode {
+    /// skip the schema compatibility check entirely
+    Off,
+    /// report schema incompatibilities through tracing but continue the sync
+    Warn,
+    /// report schema incompatibilities through tracing and abort before any writes are made
+    Abort,
+}
+
+impl std::fmt::Display for SchemaCheckMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaCheckMode::Off => write!(f, "off"),
+            SchemaCheckMode::Warn => write!(f, "warn"),
+            SchemaCheckMode::Abort => write!(f, "abort"),
+        }
+    }
+}
+
 /// Command-line options
 #[derive(clap::Parser, Debug)]
 #[clap(name = clap::crate_name!(),
@@ -83,46 +164,56 @@ struct Options {
     )]
     delete: bool,
 
-    /// Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the source LDAP server
+    /// Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the source LDAP server; required unless --job-file is given
     #[clap(
         long,
-        help = "Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the source LDAP server"
+        required_unless_present = "job_file",
+        help = "Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the source LDAP server; required unless --job-file is given"
     )]
-    source_ldap_server: std::path::PathBuf,
+    source_ldap_server: Option<std::path::PathBuf>,
 
-    /// base DN for the search to perform on the source LDAP server
+    /// base DN for the search to perform on the source LDAP server; required unless --job-file is given
     #[clap(
         long,
-        help = "base DN for the search to perform on the source LDAP server"
+        required_unless_present = "job_file",
+        help = "base DN for the search to perform on the source LDAP server; required unless --job-file is given"
     )]
-    source_search_base: std::string::String,
+    source_search_base: Option<std::string::String>,
 
-    /// Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the destination LDAP server
+    /// Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the destination LDAP server; required unless --job-file is given
     #[clap(
         long,
-        help = "Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the destination LDAP server"
+        required_unless_present = "job_file",
+        help = "Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the destination LDAP server; required unless --job-file is given"
     )]
-    destination_ldap_server: std::path::PathBuf,
+    destination_ldap_server: Option<std::path::PathBuf>,
 
-    /// base DN for the search to perform on the destination LDAP server
+    /// base DN for the search to perform on the destination LDAP server; required unless --job-file is given
     #[clap(
         long,
-        help = "base DN for the search to perform on the destination LDAP server"
+        required_unless_present = "job_file",
+        help = "base DN for the search to perform on the destination LDAP server; required unless --job-file is given"
     )]
-    destination_search_base: std::string::String,
+    destination_search_base: Option<std::string::String>,
 
-    /// scope for the search
-    #[clap(long, value_parser = parse_scope)]
-    search_scope: ldap3::Scope,
+    /// scope for the search; required unless --job-file is given
+    #[clap(
+        long,
+        value_parser = parse_scope,
+        required_unless_present = "job_file",
+        help = "scope for the search; required unless --job-file is given"
+    )]
+    search_scope: Option<ldap3::Scope>,
 
     /// LDAP search filter for the search on the source LDAP server
     /// for the destination LDAP server the DN-valued attributes are
-    /// automatically transformed to the destination base DN
+    /// automatically transformed to the destination base DN; required unless --job-file is given
     #[clap(
         long,
-        help = "LDAP search filter for the search on the source LDAP server, for the destination LDAP server the DN-valued attributes are automatically transformed to the destination base DN"
+        required_unless_present = "job_file",
+        help = "LDAP search filter for the search on the source LDAP server, for the destination LDAP server the DN-valued attributes are automatically transformed to the destination base DN; required unless --job-file is given"
     )]
-    search_filter: std::string::String,
+    search_filter: Option<std::string::String>,
 
     /// if specified only transfer these attributes
     #[clap(long = "attribute", number_of_values = 1)]
@@ -149,6 +240,180 @@ struct Options {
         help = "if this is specified all children of matched entries are also transferred, this is necessary because those children themselves might not have any objectclasses or attributes to use in the search filter"
     )]
     include_children: bool,
+
+    /// if specified, searches use the RFC 2696 Simple Paged Results control with this page size instead of issuing a single unbounded search; falls back to an unpaged search if the server does not advertise the control
+    #[clap(
+        long,
+        help = "if specified, searches use the RFC 2696 Simple Paged Results control with this page size instead of issuing a single unbounded search; falls back to an unpaged search if the server does not advertise the control"
+    )]
+    page_size: Option<i32>,
+
+    /// alias dereferencing behavior to use for all searches; note that setting this to "search" or "always" means the destination diff will see dereferenced content instead of the literal alias entries, which is rarely what a subtree replicator wants
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = DerefAliases::Never,
+        help = "alias dereferencing behavior to use for all searches (never|search|find|always); with \"search\" or \"always\" the destination diff will see dereferenced content instead of the literal alias entries, which is rarely what a subtree replicator wants"
+    )]
+    deref: DerefAliases,
+
+    /// Name of a TOML file describing multiple subtree sync jobs to run sequentially; when given, the single-job options above (source/destination servers, search bases, scope, filter, attribute/objectclass lists, add/update/delete, include-children) are ignored in favor of the jobs listed in the file
+    #[clap(
+        long,
+        help = "Name of a TOML file describing multiple subtree sync jobs to run sequentially; when given, the single-job options above are ignored in favor of the jobs listed in the file"
+    )]
+    job_file: Option<std::path::PathBuf>,
+
+    /// whether and how to check schema compatibility between source and destination before syncing (off|warn|abort)
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = SchemaCheckMode::Off,
+        help = "whether and how to check schema compatibility between source and destination before syncing: off does not check, warn reports incompatibilities through tracing but continues, abort reports them and returns an error before any writes are made"
+    )]
+    schema_check: SchemaCheckMode,
+
+    /// if specified, write the planned changes as an RFC 2849 LDIF change record document to this path, in addition to (or, with --dry-run, instead of) applying them
+    #[clap(
+        long,
+        help = "if specified, write the planned changes as an RFC 2849 LDIF change record document to this path, in addition to (or, with --dry-run, instead of) applying them"
+    )]
+    output_ldif: Option<std::path::PathBuf>,
+
+    /// rename a source attribute to a different name at the destination, and rewrite matching RDN components of DN-valued attribute values accordingly, e.g. --rename-attribute uid=cn; may be given multiple times
+    #[clap(
+        long = "rename-attribute",
+        value_parser = parse_attribute_rename,
+        help = "rename a source attribute to a different name at the destination, and rewrite matching RDN components of DN-valued attribute values accordingly, e.g. --rename-attribute uid=cn; may be given multiple times"
+    )]
+    rename_attributes: Vec<(std::string::String, std::string::String)>,
+}
+
+/// Parse a `--rename-attribute source=dest` command line argument into a (source, destination) attribute name pair
+fn parse_attribute_rename(
+    value: &str,
+) -> Result<(std::string::String, std::string::String), std::string::String> {
+    match value.split_once('=') {
+        Some((source, destination)) if !source.is_empty() && !destination.is_empty() => {
+            Ok((source.to_owned(), destination.to_owned()))
+        }
+        _ => Err(format!("expected SOURCE=DEST (e.g. uid=cn), got {value:?}")),
+    }
+}
+
+/// Parameters describing a single source to destination subtree sync job,
+/// either built from the top-level CLI options or loaded from a `--job-file`
+#[derive(Deserialize, Debug)]
+struct Job {
+    /// Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the source LDAP server
+    source_ldap_server: std::path::PathBuf,
+
+    /// base DN for the search to perform on the source LDAP server
+    source_search_base: std::string::String,
+
+    /// Name of TOML file describing the CA certificate, client certificate, client key and LDAP URI for the destination LDAP server
+    destination_ldap_server: std::path::PathBuf,
+
+    /// base DN for the search to perform on the destination LDAP server
+    destination_search_base: std::string::String,
+
+    /// scope for the search
+    #[serde(deserialize_with = "deserialize_scope")]
+    search_scope: ldap3::Scope,
+
+    /// LDAP search filter for the search on the source LDAP server
+    /// for the destination LDAP server the DN-valued attributes are
+    /// automatically transformed to the destination base DN
+    search_filter: std::string::String,
+
+    /// if specified only transfer these attributes
+    #[serde(default)]
+    attributes: Vec<std::string::String>,
+
+    /// if specified ignore these object classes when transferring data (useful e.g. to avoid transferring passwords or similar local information)
+    #[serde(default)]
+    ignore_object_classes: Vec<std::string::String>,
+
+    /// if specified ignore these attributes when transferring data (useful e.g. to avoid transferring passwords or similar local information)
+    #[serde(default)]
+    ignore_attributes: Vec<std::string::String>,
+
+    /// add entries missing at the destination
+    #[serde(default)]
+    add: bool,
+
+    /// update entries with differing attributes and/or objectclasses at the destination
+    #[serde(default)]
+    update: bool,
+
+    /// delete entries at the destination not present at the source
+    #[serde(default)]
+    delete: bool,
+
+    /// if this is specified all children of matched entries are also transferred, this is necessary because those children themselves might not have any objectclasses or attributes to use in the search filter
+    #[serde(default)]
+    include_children: bool,
+
+    /// mapping of source attribute names to destination attribute names, applied the same way as repeated `--rename-attribute` flags
+    #[serde(default)]
+    rename_attributes: HashMap<std::string::String, std::string::String>,
+}
+
+impl From<&Options> for Job {
+    /// Builds a single job from the top-level CLI options; only called when
+    /// `--job-file` was not given, in which case clap's
+    /// `required_unless_present` already guarantees these fields are present
+    fn from(options: &Options) -> Self {
+        Job {
+            source_ldap_server: options
+                .source_ldap_server
+                .clone()
+                .expect("required by clap when --job-file is not given"),
+            source_search_base: options
+                .source_search_base
+                .clone()
+                .expect("required by clap when --job-file is not given"),
+            destination_ldap_server: options
+                .destination_ldap_server
+                .clone()
+                .expect("required by clap when --job-file is not given"),
+            destination_search_base: options
+                .destination_search_base
+                .clone()
+                .expect("required by clap when --job-file is not given"),
+            search_scope: options
+                .search_scope
+                .expect("required by clap when --job-file is not given"),
+            search_filter: options
+                .search_filter
+                .clone()
+                .expect("required by clap when --job-file is not given"),
+            attributes: options.attributes.clone(),
+            ignore_object_classes: options.ignore_object_classes.clone(),
+            ignore_attributes: options.ignore_attributes.clone(),
+            add: options.add,
+            update: options.update,
+            delete: options.delete,
+            include_children: options.include_children,
+            rename_attributes: options.rename_attributes.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Top-level structure of a `--job-file`: a list of subtree sync jobs to run sequentially, in order
+#[derive(Deserialize, Debug)]
+struct JobFile {
+    /// the jobs to run, in order
+    job: Vec<Job>,
+}
+
+/// Deserialize an LDAP search scope from its string representation (base|one|subtree), reusing the same parser as the `--search-scope` CLI option
+fn deserialize_scope<'de, D>(deserializer: D) -> Result<ldap3::Scope, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = std::string::String::deserialize(deserializer)?;
+    parse_scope(&value).map_err(serde::de::Error::custom)
 }
 
 #[tokio::main]
@@ -172,14 +437,255 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     let options = <Options as clap::Parser>::try_parse()?;
     tracing::debug!("{:#?}", options);
 
-    lazy_static! {
-        static ref DN_SYNTAX_OID: OIDWithLength = OIDWithLength {
-            oid: ObjectIdentifier::try_from("1.3.6.1.4.1.1466.115.121.1.12").unwrap(),
-            length: None
-        };
+    let jobs = match &options.job_file {
+        Some(job_file) => {
+            let job_file_contents = std::fs::read_to_string(job_file)?;
+            let job_file: JobFile = toml::from_str(&job_file_contents)?;
+            job_file.job
+        }
+        None => vec![Job::from(&options)],
+    };
+
+    if let Some(output_ldif) = &options.output_ldif {
+        std::fs::write(output_ldif, "")?;
+    }
+
+    let mut failed_jobs = 0;
+    for job in &jobs {
+        tracing::debug!("Running job {:#?}", job);
+        if let Err(e) = run_job(
+            job,
+            options.dry_run,
+            options.page_size,
+            options.deref,
+            options.schema_check,
+            options.output_ldif.as_deref(),
+        )
+        .await
+        {
+            tracing::error!(
+                "Job {} -> {} failed: {}",
+                job.source_search_base,
+                job.destination_search_base,
+                e
+            );
+            failed_jobs += 1;
+        }
+    }
+
+    if failed_jobs == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} of {} job(s) failed", failed_jobs, jobs.len()).into())
+    }
+}
+
+/// The control OID for the RFC 2696 Simple Paged Results control
+const PAGED_RESULTS_CONTROL_OID: &str = "1.2.840.113556.1.4.319";
+
+/// Whether `root_dse` advertises support for the RFC 2696 Simple Paged
+/// Results control via its `supportedControl` attribute
+fn root_dse_supports_paged_results(root_dse: &LDAPEntry) -> bool {
+    root_dse
+        .attributes
+        .get("supportedControl")
+        .into_iter()
+        .flatten()
+        .any(|oid| oid == PAGED_RESULTS_CONTROL_OID)
+}
+
+/// BER-encodes a length per the short-form (values 0-127) or long-form
+/// (a length-of-the-length byte followed by the big-endian length) rules;
+/// a paged-results cookie routinely exceeds 127 bytes (e.g. Active
+/// Directory), so the long form must be supported, not just the short form
+fn encode_ber_length(length: usize) -> Vec<u8> {
+    if length < 0x80 {
+        vec![length as u8]
+    } else {
+        let mut length_bytes = length.to_be_bytes().to_vec();
+        while length_bytes.len() > 1 && length_bytes[0] == 0 {
+            length_bytes.remove(0);
+        }
+        let mut encoded = vec![0x80 | length_bytes.len() as u8];
+        encoded.extend(length_bytes);
+        encoded
+    }
+}
+
+/// BER-encodes the value of an RFC 2696 Simple Paged Results request
+/// control: `realSearchControlValue ::= SEQUENCE { size INTEGER, cookie OCTET STRING }`
+fn encode_paged_results_control_value(size: i32, cookie: &[u8]) -> Vec<u8> {
+    let mut size_bytes = size.to_be_bytes().to_vec();
+    while size_bytes.len() > 1 && size_bytes[0] == 0 && size_bytes[1] & 0x80 == 0 {
+        size_bytes.remove(0);
+    }
+    let mut sequence = vec![0x02];
+    sequence.extend(encode_ber_length(size_bytes.len()));
+    sequence.extend_from_slice(&size_bytes);
+    sequence.push(0x04);
+    sequence.extend(encode_ber_length(cookie.len()));
+    sequence.extend_from_slice(cookie);
+    let mut value = vec![0x30];
+    value.extend(encode_ber_length(sequence.len()));
+    value.extend(sequence);
+    value
+}
+
+/// Reads a single BER short-form or long-form length at `offset`, returning `(length, offset_after_length)`
+fn read_ber_length(value: &[u8], offset: usize) -> Option<(usize, usize)> {
+    let first = *value.get(offset)?;
+    if first & 0x80 == 0 {
+        Some((first as usize, offset + 1))
+    } else {
+        let num_bytes = (first & 0x7f) as usize;
+        let mut length = 0usize;
+        for i in 0..num_bytes {
+            length = (length << 8) | usize::from(*value.get(offset + 1 + i)?);
+        }
+        Some((length, offset + 1 + num_bytes))
+    }
+}
+
+/// Decodes the value of an RFC 2696 Simple Paged Results response control,
+/// returning the server's `cookie`, if `value` is a well-formed
+/// `SEQUENCE { size INTEGER, cookie OCTET STRING }`
+fn decode_paged_results_control_cookie(value: &[u8]) -> Option<Vec<u8>> {
+    if *value.first()? != 0x30 {
+        return None;
+    }
+    let (_sequence_len, offset) = read_ber_length(value, 1)?;
+    if *value.get(offset)? != 0x02 {
+        return None;
+    }
+    let (size_len, size_offset) = read_ber_length(value, offset + 1)?;
+    let offset = size_offset + size_len;
+    if *value.get(offset)? != 0x04 {
+        return None;
+    }
+    let (cookie_len, cookie_offset) = read_ber_length(value, offset + 1)?;
+    value
+        .get(cookie_offset..cookie_offset + cookie_len)
+        .map(<[u8]>::to_vec)
+}
+
+/// Canonicalizes the trailing `ldap_base_dn` portion of `dn` to exactly
+/// match the configured base DN, leaving the rest of `dn` untouched. Servers
+/// sometimes return entry DNs whose base suffix differs in case from the
+/// base DN as configured; without this, the same entry would be keyed
+/// differently depending on which server returned it, breaking the
+/// source/destination entry map comparison. Only ASCII case differences are
+/// recognized: a suffix that differs in whitespace around RDN separators is
+/// not matched, and such a `dn` is returned unchanged.
+fn normalize_dn_base(dn: &str, ldap_base_dn: &str) -> std::string::String {
+    let Some(prefix_len) = dn.len().checked_sub(ldap_base_dn.len()) else {
+        return dn.to_owned();
+    };
+    let is_base_suffix = dn[prefix_len..].eq_ignore_ascii_case(ldap_base_dn)
+        && (prefix_len == 0 || dn.as_bytes()[prefix_len - 1] == b',');
+    if is_base_suffix {
+        format!("{}{}", &dn[..prefix_len], ldap_base_dn)
+    } else {
+        dn.to_owned()
+    }
+}
+
+/// Converts a raw `ldap3::ResultEntry` into an `LDAPEntry` (via
+/// `ldap3::SearchEntry`) and inserts it into `entries`, keyed by its DN with
+/// the `ldap_base_dn` suffix canonicalized via `normalize_dn_base`
+fn insert_search_entry(
+    entries: &mut HashMap<String, LDAPEntry>,
+    result_entry: ldap3::ResultEntry,
+    ldap_base_dn: &str,
+) {
+    let search_entry = SearchEntry::construct(result_entry);
+    let dn = normalize_dn_base(&search_entry.dn, ldap_base_dn);
+    entries.insert(
+        dn.clone(),
+        LDAPEntry {
+            dn,
+            attributes: search_entry.attrs,
+        },
+    );
+}
+
+/// Searches `search_base`, applying `deref` via the search options and, if
+/// `page_size` is given and `root_dse` advertises support for it, paging
+/// through the results with the RFC 2696 Simple Paged Results control
+/// instead of relying on the server's (or client's) default size limit.
+/// Falls back to a single unpaged search if `page_size` is `None` or the
+/// control is not advertised by `root_dse`. Matching entries are inserted
+/// into `entries`, keyed by DN with the `ldap_base_dn` suffix canonicalized.
+#[allow(clippy::too_many_arguments)]
+async fn search_entries(
+    ldap: &mut ldap3::Ldap,
+    ldap_base_dn: &str,
+    search_base: &str,
+    scope: ldap3::Scope,
+    filter: &str,
+    attributes: &[std::string::String],
+    entries: &mut HashMap<String, LDAPEntry>,
+    root_dse: &LDAPEntry,
+    page_size: Option<i32>,
+    deref: DerefAliases,
+) -> Result<(), Box<dyn Error>> {
+    let attrs: Vec<&str> = attributes.iter().map(std::string::String::as_str).collect();
+    let search_options = SearchOptions::new().deref(deref.into());
+
+    if let Some(page_size) = page_size.filter(|_| root_dse_supports_paged_results(root_dse)) {
+        let mut cookie: Vec<u8> = Vec::new();
+        loop {
+            let paged_results_control = RawControl {
+                ctype: PAGED_RESULTS_CONTROL_OID.to_owned(),
+                crit: false,
+                val: Some(encode_paged_results_control_value(page_size, &cookie)),
+            };
+            let (result_entries, ldap_result) = ldap
+                .with_controls(vec![paged_results_control])
+                .with_search_options(search_options.clone())
+                .search(search_base, scope, filter, attrs.clone())
+                .await?
+                .success()?;
+            for result_entry in result_entries {
+                insert_search_entry(entries, result_entry, ldap_base_dn);
+            }
+            let next_cookie = ldap_result
+                .ctrls
+                .iter()
+                .find(|ctrl| ctrl.ctype == PAGED_RESULTS_CONTROL_OID)
+                .and_then(|ctrl| ctrl.val.as_deref())
+                .and_then(decode_paged_results_control_cookie);
+            match next_cookie {
+                Some(next_cookie) if !next_cookie.is_empty() => cookie = next_cookie,
+                _ => break,
+            }
+        }
+    } else {
+        let (result_entries, _ldap_result) = ldap
+            .with_search_options(search_options)
+            .search(search_base, scope, filter, attrs)
+            .await?
+            .success()?;
+        for result_entry in result_entries {
+            insert_search_entry(entries, result_entry, ldap_base_dn);
+        }
     }
 
-    let source_connect_parameters = toml_connect_parameters(options.source_ldap_server.to_owned())?;
+    Ok(())
+}
+
+/// Run a single source to destination subtree sync job: connect to both
+/// servers, fetch their schemas, search, diff and, unless `dry_run` is set,
+/// apply the resulting changes to the destination
+#[instrument]
+async fn run_job(
+    job: &Job,
+    dry_run: bool,
+    page_size: Option<i32>,
+    deref: DerefAliases,
+    schema_check: SchemaCheckMode,
+    output_ldif: Option<&std::path::Path>,
+) -> Result<(), Box<dyn Error>> {
+    let source_connect_parameters = toml_connect_parameters(job.source_ldap_server.to_owned())?;
     tracing::debug!(
         "Source connect parameters\n{:#?}",
         source_connect_parameters
@@ -195,7 +701,7 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     tracing::debug!("Source LDAP schema {:#?}", source_ldap_schema);
 
     let destination_connect_parameters =
-        toml_connect_parameters(options.destination_ldap_server.to_owned())?;
+        toml_connect_parameters(job.destination_ldap_server.to_owned())?;
     tracing::debug!(
         "Destination connect parameters\n{:#?}",
         destination_connect_parameters
@@ -211,7 +717,7 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     tracing::debug!("Destination LDAP schema {:#?}", destination_ldap_schema);
 
     let source_search_filter = search_filter_parser()
-        .parse(options.search_filter.clone())
+        .parse(job.search_filter.clone())
         .expect("Failed to parse search filter");
     tracing::debug!("Source search filter: {:#?}", source_search_filter);
     let destination_search_filter = source_search_filter.transform_base_dns(
@@ -225,7 +731,7 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     );
     tracing::debug!(
         "Search filter as passed in:          {}",
-        &options.search_filter
+        &job.search_filter
     );
     let source_search_filter = source_search_filter.to_string();
     let destination_search_filter = destination_search_filter.to_string();
@@ -243,15 +749,18 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     search_entries(
         &mut source_ldap,
         &source_base_dn,
-        &options.source_search_base,
-        options.search_scope,
+        &job.source_search_base,
+        job.search_scope,
         &source_search_filter,
-        options.attributes.as_slice(),
+        job.attributes.as_slice(),
         &mut source_entries,
+        &source_root_dse,
+        page_size,
+        deref,
     )
     .await?;
 
-    if options.include_children {
+    if job.include_children {
         let source_base_query_dns = source_entries.keys().cloned().collect::<Vec<String>>();
         for dn in &source_base_query_dns {
             tracing::debug!("Performing subtree query for source entry {}", &dn);
@@ -261,8 +770,11 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
                 dn,
                 ldap3::Scope::Subtree,
                 "(objectClass=*)",
-                options.attributes.as_slice(),
+                job.attributes.as_slice(),
                 &mut source_entries,
+                &source_root_dse,
+                page_size,
+                deref,
             )
             .await?;
         }
@@ -273,15 +785,18 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     search_entries(
         &mut destination_ldap,
         &destination_base_dn,
-        &options.destination_search_base,
-        options.search_scope,
+        &job.destination_search_base,
+        job.search_scope,
         &destination_search_filter,
-        options.attributes.as_slice(),
+        job.attributes.as_slice(),
         &mut destination_entries,
+        &destination_root_dse,
+        page_size,
+        deref,
     )
     .await?;
 
-    if options.include_children {
+    if job.include_children {
         let destination_base_query_dns =
             destination_entries.keys().cloned().collect::<Vec<String>>();
         for dn in &destination_base_query_dns {
@@ -292,8 +807,11 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
                 dn,
                 ldap3::Scope::Subtree,
                 "(objectClass=*)",
-                options.attributes.as_slice(),
+                job.attributes.as_slice(),
                 &mut destination_entries,
+                &destination_root_dse,
+                page_size,
+                deref,
             )
             .await?;
         }
@@ -302,22 +820,74 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     tracing::debug!("Source entries:\n{:#?}", source_entries);
     tracing::debug!("Destination entries:\n{:#?}", destination_entries);
 
+    if schema_check != SchemaCheckMode::Off {
+        let schema_mismatches = check_schema_compatibility(
+            &source_entries,
+            &source_ldap_schema,
+            &destination_ldap_schema,
+            &job.rename_attributes,
+            &job.ignore_object_classes,
+            &job.ignore_attributes,
+        );
+        for schema_mismatch in &schema_mismatches {
+            tracing::warn!("Schema compatibility: {}", schema_mismatch);
+        }
+        if schema_check == SchemaCheckMode::Abort && !schema_mismatches.is_empty() {
+            return Err(format!(
+                "{} schema incompatibilit{} found between source and destination schema, aborting before any writes",
+                schema_mismatches.len(),
+                if schema_mismatches.len() == 1 { "y" } else { "ies" }
+            )
+            .into());
+        }
+    }
+
+    if !job.rename_attributes.is_empty() {
+        let mut renamed_entries: HashMap<String, LDAPEntry> =
+            HashMap::with_capacity(source_entries.len());
+        for entry in source_entries.into_values() {
+            let original_dn = entry.dn.clone();
+            let renamed_entry =
+                apply_attribute_renames(&entry, &job.rename_attributes, &source_ldap_schema);
+            let renamed_dn = renamed_entry.dn.clone();
+            if renamed_entries
+                .insert(renamed_dn.clone(), renamed_entry)
+                .is_some()
+            {
+                return Err(format!(
+                    "attribute rename produced a DN collision: more than one source entry (including {original_dn:?}) renames to {renamed_dn}"
+                )
+                .into());
+            }
+        }
+        source_entries = renamed_entries;
+    }
+
     let ldap_operations = diff_entries(
         &source_entries,
         &destination_entries,
         &source_base_dn,
         &destination_base_dn,
-        &options.ignore_object_classes,
-        &options.ignore_attributes,
+        &job.ignore_object_classes,
+        &job.ignore_attributes,
         &source_ldap_schema,
-        options.add,
-        options.update,
-        options.delete,
+        job.add,
+        job.update,
+        job.delete,
     );
 
     tracing::debug!("Operations to apply: {:#?}", ldap_operations);
-    // TODO: abort sync if schemas differ between source and destination (optionally?)
-    // TODO: optionally specify details of operation in a toml file instead of the CLI (I think clap or structopt has some options there?)
+
+    if let Some(output_ldif) = output_ldif {
+        let ldif = ldap_operations_to_ldif(&ldap_operations);
+        tracing::debug!("Appending planned changes as LDIF to {:?}", output_ldif);
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output_ldif)?;
+        file.write_all(ldif.as_bytes())?;
+    }
 
     // apparently some (e.g. delete) operations do not work properly with no-op control (e.g. the
     // object is gone from further searches until the next slapd restart)
@@ -329,7 +899,7 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
     //)
     //.await?;
 
-    if !options.dry_run {
+    if !dry_run {
         apply_ldap_operations(
             &mut destination_ldap,
             &destination_base_dn,
@@ -341,3 +911,292 @@ async fn do_sync() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Walk every attribute type and object class referenced by `entries`,
+/// except those listed in `ignore_object_classes`/`ignore_attributes` (which
+/// `diff_entries` filters out before writing, so they are never actually
+/// transferred), and verify each remaining one is defined in
+/// `destination_schema` with a syntax OID and SINGLE-VALUE flag matching its
+/// definition in `source_schema`, returning a human-readable description of
+/// each incompatibility found (missing attribute type, missing object
+/// class, differing syntax, or a single-valued/multi-valued mismatch).
+/// `rename_attributes` is consulted so that a renamed attribute is checked
+/// against its destination name rather than its original source name.
+fn check_schema_compatibility(
+    entries: &HashMap<String, LDAPEntry>,
+    source_schema: &LDAPSchema,
+    destination_schema: &LDAPSchema,
+    rename_attributes: &HashMap<std::string::String, std::string::String>,
+    ignore_object_classes: &[std::string::String],
+    ignore_attributes: &[std::string::String],
+) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let mut checked_attributes = std::collections::HashSet::new();
+    let mut checked_object_classes = std::collections::HashSet::new();
+
+    for entry in entries.values() {
+        if let Some(object_classes) = entry.attributes.get("objectClass") {
+            for object_class in object_classes {
+                if ignore_object_classes
+                    .iter()
+                    .any(|ignored| ignored.eq_ignore_ascii_case(object_class))
+                {
+                    continue;
+                }
+                if !checked_object_classes.insert(object_class.to_lowercase()) {
+                    continue;
+                }
+                if destination_schema
+                    .object_classes
+                    .get(&object_class.to_lowercase())
+                    .is_none()
+                {
+                    mismatches.push(format!(
+                        "object class {object_class} is not defined in the destination schema"
+                    ));
+                }
+            }
+        }
+
+        for attribute_name in entry.attributes.keys() {
+            if ignore_attributes
+                .iter()
+                .any(|ignored| ignored.eq_ignore_ascii_case(attribute_name))
+            {
+                continue;
+            }
+            if !checked_attributes.insert(attribute_name.to_lowercase()) {
+                continue;
+            }
+            let destination_attribute_name =
+                find_renamed_attribute(rename_attributes, attribute_name)
+                    .unwrap_or(attribute_name.as_str());
+            let source_attribute_type = source_schema
+                .attribute_types
+                .get(&attribute_name.to_lowercase());
+            let destination_attribute_type = destination_schema
+                .attribute_types
+                .get(&destination_attribute_name.to_lowercase());
+            match (source_attribute_type, destination_attribute_type) {
+                (_, None) => mismatches.push(format!(
+                    "attribute {attribute_name} (as {destination_attribute_name}) is not defined in the destination schema"
+                )),
+                (Some(source_attribute_type), Some(destination_attribute_type)) => {
+                    if source_attribute_type.syntax != destination_attribute_type.syntax {
+                        mismatches.push(format!(
+                            "attribute {attribute_name} has syntax {:?} at the source but {destination_attribute_name} has syntax {:?} at the destination",
+                            source_attribute_type.syntax, destination_attribute_type.syntax
+                        ));
+                    }
+                    if source_attribute_type.single_value != destination_attribute_type.single_value
+                    {
+                        mismatches.push(format!(
+                            "attribute {attribute_name} is {} at the source but {destination_attribute_name} is {} at the destination",
+                            if source_attribute_type.single_value { "single-valued" } else { "multi-valued" },
+                            if destination_attribute_type.single_value { "single-valued" } else { "multi-valued" },
+                        ));
+                    }
+                }
+                (None, Some(_)) => (),
+            }
+        }
+    }
+
+    mismatches
+}
+
+/// Serialize `ldap_operations` as an RFC 2849 LDIF change record document,
+/// one `dn:`-led change record per operation, suitable for review or for
+/// later application with `ldapmodify`
+fn ldap_operations_to_ldif(ldap_operations: &[LDAPOperation]) -> String {
+    let mut ldif = String::new();
+    for ldap_operation in ldap_operations {
+        match ldap_operation {
+            LDAPOperation::Add(dn, attributes) => {
+                push_ldif_line(&mut ldif, "dn", dn);
+                ldif.push_str("changetype: add\n");
+                for (attribute, values) in attributes {
+                    for value in values {
+                        push_ldif_line(&mut ldif, attribute, value);
+                    }
+                }
+            }
+            LDAPOperation::Modify(dn, modifications) => {
+                push_ldif_line(&mut ldif, "dn", dn);
+                ldif.push_str("changetype: modify\n");
+                for modification in modifications {
+                    let (verb, attribute, values) = match modification {
+                        Mod::Add(attribute, values) => ("add", attribute, values),
+                        Mod::Delete(attribute, values) => ("delete", attribute, values),
+                        Mod::Replace(attribute, values) => ("replace", attribute, values),
+                    };
+                    ldif.push_str(&format!("{verb}: {attribute}\n"));
+                    for value in values {
+                        push_ldif_line(&mut ldif, attribute, value);
+                    }
+                    ldif.push_str("-\n");
+                }
+            }
+            LDAPOperation::Delete(dn) => {
+                push_ldif_line(&mut ldif, "dn", dn);
+                ldif.push_str("changetype: delete\n");
+            }
+        }
+        ldif.push('\n');
+    }
+    ldif
+}
+
+/// Append one LDIF attribute/value line (`name: value` or, if `value`
+/// requires it, `name:: <base64>`) to `ldif`
+fn push_ldif_line(ldif: &mut String, name: &str, value: &str) {
+    if ldif_value_needs_base64(value) {
+        ldif.push_str(&format!("{name}:: {}\n", base64_encode(value.as_bytes())));
+    } else {
+        ldif.push_str(&format!("{name}: {value}\n"));
+    }
+}
+
+/// Whether `value` must be base64-encoded to appear as an LDIF attribute
+/// value, per the RFC 2849 SAFE-STRING grammar: empty, starting with a
+/// space, colon or less-than sign, ending with a space, or containing a NUL,
+/// LF, CR or any non-ASCII byte
+fn ldif_value_needs_base64(value: &str) -> bool {
+    if value.is_empty() {
+        return true;
+    }
+    let bytes = value.as_bytes();
+    if matches!(bytes[0], b' ' | b':' | b'<') {
+        return true;
+    }
+    // RFC 2849 LDIF readers strip trailing whitespace from a plain
+    // `name: value` line, so a value ending in a space must be base64-encoded
+    // to round-trip correctly.
+    if bytes[bytes.len() - 1] == b' ' {
+        return true;
+    }
+    bytes
+        .iter()
+        .any(|&b| b == 0 || b == b'\n' || b == b'\r' || b >= 0x80)
+}
+
+/// Encode `value` as base64 (RFC 4648 standard alphabet, with padding)
+fn base64_encode(value: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(value.len().div_ceil(3) * 4);
+    for chunk in value.chunks(3) {
+        let b0 = chunk[0];
+        let b1 = chunk.get(1).copied().unwrap_or(0);
+        let b2 = chunk.get(2).copied().unwrap_or(0);
+        out.push(ALPHABET[(b0 >> 2) as usize] as char);
+        out.push(ALPHABET[(((b0 & 0x03) << 4) | (b1 >> 4)) as usize] as char);
+        out.push(if chunk.len() > 1 {
+            ALPHABET[(((b1 & 0x0f) << 2) | (b2 >> 6)) as usize] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            ALPHABET[(b2 & 0x3f) as usize] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
+
+/// Look up `attribute_name` in `rename_attributes` case-insensitively, as
+/// LDAP attribute names are, returning the configured destination name if
+/// one matches
+fn find_renamed_attribute<'a>(
+    rename_attributes: &'a HashMap<std::string::String, std::string::String>,
+    attribute_name: &str,
+) -> Option<&'a str> {
+    rename_attributes.iter().find_map(|(source, destination)| {
+        source
+            .eq_ignore_ascii_case(attribute_name)
+            .then_some(destination.as_str())
+    })
+}
+
+/// Rename attribute keys of `entry` according to `rename_attributes`, and
+/// rewrite matching RDN components of its `dn` and its DN-valued attribute
+/// values accordingly, beyond the plain base DN swap already performed by
+/// `search_filter.transform_base_dns`. Applying the same mapping on every
+/// run keeps re-running the sync idempotent.
+fn apply_attribute_renames(
+    entry: &LDAPEntry,
+    rename_attributes: &HashMap<std::string::String, std::string::String>,
+    schema: &LDAPSchema,
+) -> LDAPEntry {
+    let mut renamed_attributes: HashMap<std::string::String, Vec<std::string::String>> =
+        HashMap::new();
+    for (attribute_name, values) in &entry.attributes {
+        let is_dn_valued = schema
+            .attribute_types
+            .get(&attribute_name.to_lowercase())
+            .is_some_and(|attribute_type| attribute_type.syntax == Some(DN_SYNTAX_OID.clone()));
+        let renamed_values: Vec<std::string::String> = if is_dn_valued {
+            values
+                .iter()
+                .map(|value| rename_dn_components(value, rename_attributes))
+                .collect()
+        } else {
+            values.clone()
+        };
+        let renamed_name = find_renamed_attribute(rename_attributes, attribute_name)
+            .map(std::string::String::from)
+            .unwrap_or_else(|| attribute_name.clone());
+        renamed_attributes
+            .entry(renamed_name)
+            .or_default()
+            .extend(renamed_values);
+    }
+    LDAPEntry {
+        dn: rename_dn_components(&entry.dn, rename_attributes),
+        attributes: renamed_attributes,
+    }
+}
+
+/// Split a DN into its RDN components on top-level commas, respecting
+/// backslash-escaped commas within an RDN value (RFC 4514)
+fn split_dn_components(dn: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    let mut start = 0;
+    let bytes = dn.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b',' => {
+                components.push(&dn[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    components.push(&dn[start..]);
+    components
+}
+
+/// Rewrite the attribute name of each RDN component of `dn` according to
+/// `rename_attributes`, e.g. `uid=jdoe,ou=people,...` becomes
+/// `cn=jdoe,ou=people,...` when `uid` is mapped to `cn`
+fn rename_dn_components(
+    dn: &str,
+    rename_attributes: &HashMap<std::string::String, std::string::String>,
+) -> std::string::String {
+    split_dn_components(dn)
+        .into_iter()
+        .map(|rdn| match rdn.split_once('=') {
+            Some((attribute_name, value)) => {
+                match find_renamed_attribute(rename_attributes, attribute_name.trim()) {
+                    Some(renamed_attribute_name) => format!("{renamed_attribute_name}={value}"),
+                    None => rdn.to_owned(),
+                }
+            }
+            None => rdn.to_owned(),
+        })
+        .collect::<Vec<std::string::String>>()
+        .join(",")
+}